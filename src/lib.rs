@@ -39,10 +39,29 @@ pub enum RemoveCallbackError {
     NonexistentCallback,
 }
 
+/// One event in a propagation trace, recorded when tracing is enabled via
+/// `Reactor::enable_trace`. Use this to see why a callback did or didn't
+/// fire, and to confirm a shared sub-graph was only evaluated once.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceEvent<T> {
+    DirtyMarked(CellId),
+    Recomputed { id: ComputeCellId, old: T, new: T },
+    CallbackFired {
+        cell: ComputeCellId,
+        callback: CallbackId,
+        value: T,
+    },
+}
+
 pub struct Reactor<'a, T: Copy + PartialEq> {
     cells: HashMap<CellId, Rc<RefCell<cell::ReactorCell<'a, T>>>>,
     compute_cells: HashMap<CellId, Rc<RefCell<cell::ReactorCell<'a, T>>>>,
     rng: ThreadRng,
+    trace: RefCell<Option<Vec<TraceEvent<T>>>>,
+    // Bumped twice per `set_values` call (before the snapshot and again
+    // before settling), so `clean` can tell whether a cell has already been
+    // walked in the current pass and skip re-walking it.
+    generation: std::cell::Cell<u64>,
 }
 
 // You are guaranteed that Reactor will only be tested against types that are Copy + PartialEq.
@@ -52,20 +71,135 @@ impl<'a, T: Copy + PartialEq> Reactor<'a, T> {
             cells: HashMap::new(),
             compute_cells: HashMap::new(),
             rng: rand::thread_rng(),
+            trace: RefCell::new(None),
+            generation: std::cell::Cell::new(0),
+        }
+    }
+
+    // Turns on propagation tracing. Has no effect on behavior, only on what
+    // `take_trace` returns afterwards.
+    pub fn enable_trace(&mut self) {
+        self.trace = RefCell::new(Some(Vec::new()));
+    }
+
+    // Drains and returns the events recorded since the last call to
+    // `take_trace` (or since `enable_trace`, if this is the first call).
+    // Returns an empty vec if tracing isn't enabled.
+    pub fn take_trace(&self) -> Vec<TraceEvent<T>> {
+        match self.trace.borrow_mut().as_mut() {
+            Some(events) => std::mem::take(events),
+            None => Vec::new(),
         }
     }
 
-    /* pub fn notify_compute_cells(
-        compute_cells: &HashMap<CellId, Rc<RefCell<cell::ReactorCell<T>>>>,
-    ) {
-        for cell in compute_cells {
-            let dep_cell = &mut *cell.1.borrow_mut();
-            match &mut *dep_cell {
-                cell::ReactorCell::ComputeCell(ref mut c) => c.update_value(),
-                _ => (),
+    fn record(&self, event: TraceEvent<T>) {
+        if let Some(events) = self.trace.borrow_mut().as_mut() {
+            events.push(event);
+        }
+    }
+
+    // Looks up a cell (input or compute) by id, regardless of which map it lives in.
+    fn get_cell(&self, id: CellId) -> Option<&Rc<RefCell<cell::ReactorCell<'a, T>>>> {
+        match id {
+            CellId::Input(_) => self.cells.get(&id),
+            CellId::Compute(_) => self.compute_cells.get(&id),
+        }
+    }
+
+    // Walks the dependents graph starting at `start`, returning every compute
+    // cell transitively reachable from it, i.e. everything that might need to
+    // be recomputed when `start` changes.
+    fn reachable_compute_cells(&self, start: CellId) -> Vec<ComputeCellId> {
+        let mut seen = std::collections::HashSet::new();
+        let mut stack = vec![start];
+        let mut result = vec![];
+        while let Some(id) = stack.pop() {
+            let cell = match self.get_cell(id) {
+                Some(cell) => cell,
+                None => continue,
+            };
+            for &dependent in cell.borrow().dependents() {
+                if seen.insert(dependent) {
+                    result.push(dependent);
+                    stack.push(CellId::Compute(dependent));
+                }
             }
         }
-    }*/
+        result
+    }
+
+    // Marks every direct dependent of `id` dirty, unless already dirty.
+    // Called on an input change, and again from `clean` when a recompute's
+    // value actually changes, so an unchanged cell dirties nothing further.
+    fn mark_dirty(&self, id: CellId) {
+        let cell = match self.get_cell(id) {
+            Some(cell) => cell,
+            None => return,
+        };
+        let dependents: Vec<ComputeCellId> = cell.borrow().dependents().to_vec();
+        for dependent in dependents {
+            let dep_cell = match self.get_cell(CellId::Compute(dependent)) {
+                Some(cell) => cell,
+                None => continue,
+            };
+            if !dep_cell.borrow().is_dirty() {
+                dep_cell.borrow_mut().mark_dirty();
+                self.record(TraceEvent::DirtyMarked(CellId::Compute(dependent)));
+            }
+        }
+    }
+
+    // Recomputes `id` if it ends up dirty, recursing into dependencies first
+    // so they're clean before `id`'s own flag is checked. A cell whose value
+    // comes out unchanged dirties nothing further, stopping propagation
+    // there. `generation` skips a cell already walked this propagation, so a
+    // diamond-shaped graph isn't re-walked once per incoming path.
+    fn clean(&self, id: CellId) {
+        let cci = match id {
+            CellId::Compute(cci) => cci,
+            CellId::Input(_) => return,
+        };
+        let cell = match self.compute_cells.get(&CellId::Compute(cci)) {
+            Some(cell) => cell,
+            None => return,
+        };
+        let generation = self.generation.get();
+        if cell.borrow().cleaned_generation() == generation {
+            return;
+        }
+        cell.borrow_mut().mark_cleaned(generation);
+
+        let dep_ids: Vec<CellId> = match &*cell.borrow() {
+            cell::ReactorCell::ComputeCell(cc) => {
+                cc.cell_deps.iter().map(|(id, _)| *id).collect()
+            }
+            cell::ReactorCell::InputCell(_) => vec![],
+        };
+        for dep_id in dep_ids {
+            self.clean(dep_id);
+        }
+        if !cell.borrow().is_dirty() {
+            return;
+        }
+        let old_value = cell.borrow().value();
+        let recomputed = if let cell::ReactorCell::ComputeCell(cc) = &mut *cell.borrow_mut() {
+            let (_, recomputed) = cc.recompute();
+            recomputed
+        } else {
+            false
+        };
+        let new_value = cell.borrow().value();
+        if recomputed {
+            self.record(TraceEvent::Recomputed {
+                id: cci,
+                old: old_value,
+                new: new_value,
+            });
+        }
+        if new_value != old_value {
+            self.mark_dirty(CellId::Compute(cci));
+        }
+    }
 
     // Creates an input cell with the specified initial value, returning its ID.
     pub fn create_input(&mut self, initial: T) -> InputCellId {
@@ -74,7 +208,7 @@ impl<'a, T: Copy + PartialEq> Reactor<'a, T> {
         self.cells.insert(
             CellId::Input(ici),
             Rc::new(RefCell::new(cell::ReactorCell::InputCell(
-                cell::InputCell { value: initial },
+                cell::InputCell::new(initial),
             ))),
         );
         ici
@@ -104,23 +238,15 @@ impl<'a, T: Copy + PartialEq> Reactor<'a, T> {
         let gen_id: i32 = self.rng.gen();
         let cci = ComputeCellId(gen_id);
 
-        let mut deps = vec![];
+        let mut args = vec![];
         let mut cell_deps = vec![];
-        for dep in dependencies {
-            match self.cells.get(dep) {
-                Some(input_cell) => {
-                    let c = input_cell.borrow();
-                    deps.push(c.value());
-                    cell_deps.push(Rc::clone(input_cell));
+        for &dep in dependencies {
+            match self.get_cell(dep) {
+                Some(dep_cell) => {
+                    args.push(dep_cell.borrow().value());
+                    cell_deps.push((dep, Rc::clone(dep_cell)));
                 }
-                None => match self.compute_cells.get(dep) {
-                    Some(compute_cell) => {
-                        let c = compute_cell.borrow();
-                        deps.push(c.value());
-                        cell_deps.push(Rc::clone(compute_cell));
-                    }
-                    None => return Err(*dep),
-                },
+                None => return Err(dep),
             }
         }
 
@@ -128,13 +254,26 @@ impl<'a, T: Copy + PartialEq> Reactor<'a, T> {
             CellId::Compute(cci),
             Rc::new(RefCell::new(cell::ReactorCell::ComputeCell(
                 cell::ComputeCell {
-                    value: compute_func(&deps),
+                    value: compute_func(&args),
                     compute_func: Box::new(compute_func),
-                    callbacks: None,
-                    cell_deps: cell_deps,
+                    callbacks: HashMap::new(),
+                    cell_deps,
+                    dependents: vec![],
+                    dirty: false,
+                    last_args: Some(args),
+                    cleaned_gen: 0,
                 },
             ))),
         );
+
+        // Register the new cell as a dependent of each of its dependencies, so
+        // that setting any of them can flood dirtiness forward to this cell.
+        for &dep in dependencies {
+            if let Some(dep_cell) = self.get_cell(dep) {
+                dep_cell.borrow_mut().add_dependent(cci);
+            }
+        }
+
         Ok(cci)
     }
 
@@ -146,22 +285,18 @@ impl<'a, T: Copy + PartialEq> Reactor<'a, T> {
     // It turns out this introduces a significant amount of extra complexity to this exercise.
     // We chose not to cover this here, since this exercise is probably enough work as-is.
     pub fn value(&self, id: CellId) -> Option<T> {
-        match id {
-            CellId::Compute(_) => match self.compute_cells.get(&id) {
-                Some(cell) => {
-                    let c = cell.borrow();
-                    Some(c.value())
-                }
-                None => None,
-            },
-            CellId::Input(_) => match self.cells.get(&id) {
-                Some(cell) => {
-                    let c = cell.borrow();
-                    Some(c.value())
-                }
-                None => None,
-            },
+        if let CellId::Compute(_) = id {
+            self.clean(id);
         }
+        self.get_cell(id).map(|cell| cell.borrow().value())
+    }
+
+    // Like `value`, but returns `None` instead of panicking on a borrow
+    // conflict. Use this from inside a callback, since the reactor is shared
+    // as `Rc<RefCell<...>>` there.
+    pub fn try_value(&self, id: CellId) -> Option<T> {
+        let cell = self.get_cell(id)?;
+        cell.try_borrow().ok().map(|cell| cell.value())
     }
 
     // Sets the value of the specified input cell.
@@ -172,29 +307,95 @@ impl<'a, T: Copy + PartialEq> Reactor<'a, T> {
     // a `set_value(&mut self, new_value: T)` method on `Cell`.
     //
     // As before, that turned out to add too much extra complexity.
-    pub fn set_value(&mut self, id: InputCellId, new_value: T) -> bool {
-        match self.cells.get_mut(&CellId::Input(id)) {
-            Some(cell) => match *cell.borrow_mut() {
-                cell::ReactorCell::InputCell(ref mut ic) => {
-                    ic.set_value(new_value);
-                }
-                _ => return false,
-            },
-            None => return false,
-        }
-        // Notify compute cells
-        for cell in &self.compute_cells {
-            let compute_cell = &mut *cell.1.borrow_mut();
-            match &mut *compute_cell {
-                cell::ReactorCell::ComputeCell(ref mut c) => {
-                    c.update_value();
+    //
+    // Takes `&self`, not `&mut self`, so callbacks can call `try_value`
+    // reentrantly through an outer `Rc<RefCell<Reactor>>` borrow.
+    pub fn set_value(&self, id: InputCellId, new_value: T) -> bool {
+        self.set_values(&[(id, new_value)])
+    }
+
+    // Applies several input changes at once and propagates them in a single
+    // pass, so each affected compute cell's callbacks fire at most once for
+    // the whole batch, reporting only the net change. Returns false if any of
+    // the given cells does not exist, in which case no input is changed.
+    //
+    // Without this, calling `set_value` once per input fires callbacks on
+    // every intermediate state, which is wrong when several inputs feed a
+    // shared compute cell.
+    pub fn set_values(&self, updates: &[(InputCellId, T)]) -> bool {
+        if updates
+            .iter()
+            .any(|&(id, _)| !self.cells.contains_key(&CellId::Input(id)))
+        {
+            return false;
+        }
+
+        // Snapshot every compute cell this batch could possibly reach, before
+        // touching anything, so we know what to diff against afterwards.
+        let mut affected = vec![];
+        for &(id, _) in updates {
+            for cid in self.reachable_compute_cells(CellId::Input(id)) {
+                if !affected.contains(&cid) {
+                    affected.push(cid);
                 }
-                _ => (),
             }
         }
+        self.generation.set(self.generation.get() + 1);
+        let before: Vec<(ComputeCellId, T)> = affected
+            .iter()
+            .map(|&cid| (cid, self.value(CellId::Compute(cid)).unwrap()))
+            .collect();
+
+        for &(id, new_value) in updates {
+            if let cell::ReactorCell::InputCell(ic) =
+                &mut *self.cells[&CellId::Input(id)].borrow_mut()
+            {
+                ic.set_value(new_value);
+            }
+            self.mark_dirty(CellId::Input(id));
+        }
+
+        // Bump again so the settling pass re-walks cells `mark_dirty` just touched.
+        self.generation.set(self.generation.get() + 1);
+
+        self.settle(before);
+
         true
     }
 
+    // Cleans every cell that was snapshotted in `before` (which, via
+    // `value`, recomputes it if needed) and fires the callbacks of those
+    // whose value differs from its snapshot, exactly once each.
+    fn settle(&self, before: Vec<(ComputeCellId, T)>) {
+        // Collect the callbacks that need to fire, and the value to pass
+        // them, while cell borrows are held, then drop those borrows before
+        // invoking anything. This is what lets a callback safely call
+        // `try_value` on any cell without hitting an already-borrowed panic.
+        let mut to_fire: Vec<(ComputeCellId, CallbackId, Rc<RefCell<dyn FnMut(T) + 'a>>, T)> =
+            vec![];
+        for (cid, old_value) in before {
+            let new_value = self.value(CellId::Compute(cid)).unwrap();
+            if new_value != old_value {
+                if let cell::ReactorCell::ComputeCell(cc) =
+                    &*self.compute_cells[&CellId::Compute(cid)].borrow()
+                {
+                    for (&cbi, cb) in &cc.callbacks {
+                        to_fire.push((cid, cbi, Rc::clone(cb), new_value));
+                    }
+                }
+            }
+        }
+
+        for (cid, cbi, cb, value) in to_fire {
+            (cb.borrow_mut())(value);
+            self.record(TraceEvent::CallbackFired {
+                cell: cid,
+                callback: cbi,
+                value,
+            });
+        }
+    }
+
     // Adds a callback to the specified compute cell.
     //
     // Returns the ID of the just-added callback, or None if the cell doesn't exist.
@@ -215,17 +416,9 @@ impl<'a, T: Copy + PartialEq> Reactor<'a, T> {
         match self.compute_cells.get_mut(&CellId::Compute(id)) {
             Some(cell) => match *cell.borrow_mut() {
                 cell::ReactorCell::ComputeCell(ref mut cc) => {
-                    //cc.set_value(new_value);
                     let gen_id: i32 = self.rng.gen();
                     let cbi = CallbackId(gen_id);
-                    match cc.callbacks.as_mut() {
-                        Some(cbs) => {
-                            cbs.push(Rc::new(RefCell::new(callback)));
-                        }
-                        None => {
-                            cc.callbacks = Some(vec![Rc::new(RefCell::new(callback))]);
-                        }
-                    }
+                    cc.add_callback(cbi, Rc::new(RefCell::new(callback)));
                     Some(cbi)
                 }
                 _ => None,
@@ -244,10 +437,17 @@ impl<'a, T: Copy + PartialEq> Reactor<'a, T> {
         cell: ComputeCellId,
         callback: CallbackId,
     ) -> Result<(), RemoveCallbackError> {
-        unimplemented!(
-            "Remove the callback identified by the CallbackId {:?} from the cell {:?}",
-            callback,
-            cell,
-        )
+        match self.compute_cells.get_mut(&CellId::Compute(cell)) {
+            Some(cell) => match *cell.borrow_mut() {
+                cell::ReactorCell::ComputeCell(ref mut cc) => {
+                    match cc.callbacks.remove(&callback) {
+                        Some(_) => Ok(()),
+                        None => Err(RemoveCallbackError::NonexistentCallback),
+                    }
+                }
+                _ => Err(RemoveCallbackError::NonexistentCell),
+            },
+            None => Err(RemoveCallbackError::NonexistentCell),
+        }
     }
 }
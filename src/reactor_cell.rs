@@ -1,83 +1,139 @@
-use crate::CallbackId;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+pub mod cell {
+  use crate::{CallbackId, CellId, ComputeCellId};
+  use std::cell::RefCell;
+  use std::collections::HashMap;
+  use std::rc::Rc;
 
-pub trait Cell<T> {
-  fn value(&self) -> T;
-}
-
-#[derive(Copy, Clone)]
-pub struct InputCell<T: Copy> {
-  pub value: T,
-}
+  pub trait Cell<T> {
+    fn value(&self) -> T;
+  }
 
-impl<T: Copy> InputCell<T> {
-  pub fn set_value(&mut self, value: T) {
-    self.value = value;
+  pub struct InputCell<T: Copy> {
+    pub value: T,
+    pub dependents: Vec<ComputeCellId>,
   }
-}
 
-impl<T: Copy> Cell<T> for InputCell<T> {
-  fn value(&self) -> T {
-    self.value
+  impl<T: Copy> InputCell<T> {
+    pub fn new(value: T) -> Self {
+      InputCell {
+        value,
+        dependents: Vec::new(),
+      }
+    }
+
+    pub fn set_value(&mut self, value: T) {
+      self.value = value;
+    }
   }
-}
 
-pub struct ComputeCell<'a, T: Copy + PartialEq> {
-  pub value: T,
-  pub compute_func: Box<dyn Fn(&[T]) -> T>,
-  pub callbacks: HashMap<CallbackId, Rc<RefCell<dyn FnMut(T) + 'a>>>,
-  pub cell_deps: Vec<Rc<RefCell<ReactorCell<'a, T>>>>,
-}
+  impl<T: Copy> Cell<T> for InputCell<T> {
+    fn value(&self) -> T {
+      self.value
+    }
+  }
 
-impl<'a, T: Copy + PartialEq> ComputeCell<'a, T> {
-  pub fn add_callback(&mut self, cb_id: CallbackId, cb: Rc<RefCell<dyn FnMut(T)>>) {
-    self.callbacks.insert(cb_id, cb);
+  pub struct ComputeCell<'a, T: Copy + PartialEq> {
+    pub value: T,
+    pub compute_func: Box<dyn Fn(&[T]) -> T>,
+    pub callbacks: HashMap<CallbackId, Rc<RefCell<dyn FnMut(T) + 'a>>>,
+    pub cell_deps: Vec<(CellId, Rc<RefCell<ReactorCell<'a, T>>>)>,
+    pub dependents: Vec<ComputeCellId>,
+    pub dirty: bool,
+    // The dependency values `compute_func` was last called with, so a
+    // recompute whose fresh args match can reuse `value` instead of calling it.
+    pub last_args: Option<Vec<T>>,
+    // The Reactor generation `clean` last walked this cell's dependencies
+    // in, so a shared cell reachable via several paths is only ever
+    // re-walked once per propagation instead of once per path.
+    pub cleaned_gen: u64,
   }
-  pub fn update_value(&mut self) {
-    let mut args = vec![];
-    for dep in &self.cell_deps {
-      let dep_cell = &mut *dep.borrow_mut();
-      // Could be an Input or Compute Cell
-      match dep_cell {
-        ReactorCell::ComputeCell(ref mut c) => {
-          c.update_value();
-          args.push(c.value());
-        }
-        ReactorCell::InputCell(i) => {
-          args.push(i.value());
-        }
-      }
+
+  impl<'a, T: Copy + PartialEq> ComputeCell<'a, T> {
+    pub fn add_callback(&mut self, cb_id: CallbackId, cb: Rc<RefCell<dyn FnMut(T) + 'a>>) {
+      self.callbacks.insert(cb_id, cb);
     }
-    // Store the new value to check it against the previous one
-    let new_value = (self.compute_func)(&args);
-    if self.value != new_value {
-      for (_, cb) in &self.callbacks {
-        let mut callback = cb.borrow_mut();
-        callback(new_value);
+
+    // Recomputes this cell's value from its dependencies. Callers must make
+    // sure those dependencies are already clean before calling this. If the
+    // fresh dependency values are the same as last time, `compute_func` is
+    // skipped entirely and the cached value is reused. Returns the new value
+    // and whether `compute_func` was actually invoked, so callers can trace
+    // which cells were really re-evaluated.
+    pub fn recompute(&mut self) -> (T, bool) {
+      let args: Vec<T> = self
+        .cell_deps
+        .iter()
+        .map(|(_, dep)| dep.borrow().value())
+        .collect();
+      let recomputed = self.last_args.as_ref() != Some(&args);
+      if recomputed {
+        self.value = (self.compute_func)(&args);
+        self.last_args = Some(args);
       }
+      self.dirty = false;
+      (self.value, recomputed)
     }
-    self.value = new_value;
   }
-}
 
-impl<'a, T: Copy + PartialEq> Cell<T> for ComputeCell<'a, T> {
-  fn value(&self) -> T {
-    self.value
+  impl<'a, T: Copy + PartialEq> Cell<T> for ComputeCell<'a, T> {
+    fn value(&self) -> T {
+      self.value
+    }
   }
-}
 
-pub enum ReactorCell<'a, T: Copy + PartialEq> {
-  InputCell(InputCell<T>),
-  ComputeCell(ComputeCell<'a, T>),
-}
+  pub enum ReactorCell<'a, T: Copy + PartialEq> {
+    InputCell(InputCell<T>),
+    ComputeCell(ComputeCell<'a, T>),
+  }
 
-impl<'a, T: Copy + PartialEq> Cell<T> for ReactorCell<'a, T> {
-  fn value(&self) -> T {
-    match self {
-      ReactorCell::InputCell(ic) => ic.value(),
-      ReactorCell::ComputeCell(cc) => cc.value(),
+  impl<'a, T: Copy + PartialEq> ReactorCell<'a, T> {
+    pub fn dependents(&self) -> &[ComputeCellId] {
+      match self {
+        ReactorCell::InputCell(ic) => &ic.dependents,
+        ReactorCell::ComputeCell(cc) => &cc.dependents,
+      }
+    }
+
+    pub fn add_dependent(&mut self, dependent: ComputeCellId) {
+      match self {
+        ReactorCell::InputCell(ic) => ic.dependents.push(dependent),
+        ReactorCell::ComputeCell(cc) => cc.dependents.push(dependent),
+      }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+      match self {
+        ReactorCell::InputCell(_) => false,
+        ReactorCell::ComputeCell(cc) => cc.dirty,
+      }
+    }
+
+    pub fn mark_dirty(&mut self) {
+      if let ReactorCell::ComputeCell(cc) = self {
+        cc.dirty = true;
+      }
+    }
+
+    pub fn cleaned_generation(&self) -> u64 {
+      match self {
+        ReactorCell::InputCell(_) => 0,
+        ReactorCell::ComputeCell(cc) => cc.cleaned_gen,
+      }
+    }
+
+    pub fn mark_cleaned(&mut self, generation: u64) {
+      if let ReactorCell::ComputeCell(cc) = self {
+        cc.cleaned_gen = generation;
+      }
+    }
+  }
+
+  impl<'a, T: Copy + PartialEq> Cell<T> for ReactorCell<'a, T> {
+    fn value(&self) -> T {
+      match self {
+        ReactorCell::InputCell(ic) => ic.value(),
+        ReactorCell::ComputeCell(cc) => cc.value(),
+      }
     }
   }
 }
@@ -0,0 +1,146 @@
+use react::{CellId, Reactor, RemoveCallbackError};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn diamond_dependency_recomputes_each_node_once() {
+    let mut r = Reactor::new();
+    let input = r.create_input(1);
+
+    let b_calls = Rc::new(RefCell::new(0));
+    let b_calls_in_closure = Rc::clone(&b_calls);
+    let b = r
+        .create_compute(&[CellId::Input(input)], move |v| {
+            *b_calls_in_closure.borrow_mut() += 1;
+            v[0] + 1
+        })
+        .unwrap();
+
+    let c_calls = Rc::new(RefCell::new(0));
+    let c_calls_in_closure = Rc::clone(&c_calls);
+    let c = r
+        .create_compute(&[CellId::Input(input)], move |v| {
+            *c_calls_in_closure.borrow_mut() += 1;
+            v[0] * 2
+        })
+        .unwrap();
+
+    let d_calls = Rc::new(RefCell::new(0));
+    let d_calls_in_closure = Rc::clone(&d_calls);
+    let d = r
+        .create_compute(&[CellId::Compute(b), CellId::Compute(c)], move |v| {
+            *d_calls_in_closure.borrow_mut() += 1;
+            v[0] + v[1]
+        })
+        .unwrap();
+
+    // Creating a compute cell calls its function once, to seed its value.
+    let (before_b, before_c, before_d) = (*b_calls.borrow(), *c_calls.borrow(), *d_calls.borrow());
+
+    r.set_value(input, 5);
+
+    assert_eq!(*b_calls.borrow(), before_b + 1);
+    assert_eq!(*c_calls.borrow(), before_c + 1);
+    assert_eq!(*d_calls.borrow(), before_d + 1);
+    assert_eq!(r.value(CellId::Compute(d)), Some(16));
+}
+
+#[test]
+fn set_values_fires_callbacks_once_with_net_value() {
+    let mut r = Reactor::new();
+    let a = r.create_input(1);
+    let b = r.create_input(2);
+    let sum = r
+        .create_compute(&[CellId::Input(a), CellId::Input(b)], |v| v[0] + v[1])
+        .unwrap();
+
+    let seen = Rc::new(RefCell::new(vec![]));
+    let seen_in_callback = Rc::clone(&seen);
+    r.add_callback(sum, move |value| seen_in_callback.borrow_mut().push(value));
+
+    assert!(r.set_values(&[(a, 10), (b, 20)]));
+
+    assert_eq!(*seen.borrow(), vec![30]);
+    assert_eq!(r.value(CellId::Compute(sum)), Some(30));
+}
+
+#[test]
+fn removed_callback_never_fires_again() {
+    let mut r = Reactor::new();
+    let input = r.create_input(1);
+    let doubled = r
+        .create_compute(&[CellId::Input(input)], |v| v[0] * 2)
+        .unwrap();
+
+    let seen = Rc::new(RefCell::new(vec![]));
+    let seen_in_callback = Rc::clone(&seen);
+    let cbi = r
+        .add_callback(doubled, move |value| seen_in_callback.borrow_mut().push(value))
+        .unwrap();
+
+    r.set_value(input, 2);
+    assert_eq!(*seen.borrow(), vec![4]);
+
+    assert_eq!(r.remove_callback(doubled, cbi), Ok(()));
+    assert_eq!(
+        r.remove_callback(doubled, cbi),
+        Err(RemoveCallbackError::NonexistentCallback)
+    );
+
+    r.set_value(input, 3);
+    assert_eq!(*seen.borrow(), vec![4]);
+}
+
+#[test]
+fn callback_can_read_other_cells_via_try_value() {
+    let r = Rc::new(RefCell::new(Reactor::new()));
+    let (input, squared) = {
+        let mut reactor = r.borrow_mut();
+        let input = reactor.create_input(2);
+        let squared = reactor
+            .create_compute(&[CellId::Input(input)], |v| v[0] * v[0])
+            .unwrap();
+        (input, squared)
+    };
+
+    let seen = Rc::new(RefCell::new(None));
+    let seen_in_callback = Rc::clone(&seen);
+    let r_in_callback = Rc::clone(&r);
+    r.borrow_mut().add_callback(squared, move |value| {
+        // `set_value` below only ever takes `&self`, so this borrow doesn't
+        // conflict with the (also shared, non-mutable) borrow that's driving
+        // the propagation this callback is firing from.
+        let read_back = r_in_callback.borrow().try_value(CellId::Input(input));
+        *seen_in_callback.borrow_mut() = Some((value, read_back));
+    });
+
+    r.borrow().set_value(input, 4);
+
+    assert_eq!(*seen.borrow(), Some((16, Some(4))));
+}
+
+#[test]
+fn unchanged_value_stops_propagation() {
+    let mut r = Reactor::new();
+    let input = r.create_input(-5);
+    let clamped = r
+        .create_compute(&[CellId::Input(input)], |v| v[0].max(0))
+        .unwrap();
+
+    let doubled_calls = Rc::new(RefCell::new(0));
+    let doubled_calls_in_closure = Rc::clone(&doubled_calls);
+    let doubled = r
+        .create_compute(&[CellId::Compute(clamped)], move |v| {
+            *doubled_calls_in_closure.borrow_mut() += 1;
+            v[0] * 2
+        })
+        .unwrap();
+    let calls_after_creation = *doubled_calls.borrow();
+
+    // -5 and -1 both clamp to 0, so `doubled`'s only input never actually
+    // changes and it should never be recomputed.
+    r.set_value(input, -1);
+
+    assert_eq!(*doubled_calls.borrow(), calls_after_creation);
+    assert_eq!(r.value(CellId::Compute(doubled)), Some(0));
+}